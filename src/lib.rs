@@ -1,288 +1,532 @@
-use std::cmp::min;
 use image::*;
 use imageproc::pixelops::interpolate;
-use imageproc::stats::{histogram, ChannelHistogram};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
-pub fn clahe(input: GrayImage) -> Result<GrayImage, Box<dyn std::error::Error>> {
-    let mut output = GrayImage::new(input.dimensions().0, input.dimensions().1);
-
-    let tiles_hz = 8;
-    let tiles_vt = 8;
-    let tile_width = input.dimensions().0 / tiles_hz;
-    let tile_height = input.dimensions().1 / tiles_vt;
-    let mut lookup_tables = vec![vec![vec![0 as u8; 256]; tiles_hz as usize]; tiles_vt as usize];
+/// Tunable parameters for [`clahe`], modeled after ImageMagick's
+/// `CLAHEImage(width, height, number_bins, clip_limit)` signature.
+/// `clip_limit` is a multiple of the mean tile occupancy (`tile_pixel_count / number_bins`)
+/// rather than an absolute pixel count, so it stays comparable across image resolutions.
+#[derive(Copy, Clone, Debug)]
+pub struct ClaheConfig {
+    /// Number of tiles across the image horizontally.
+    pub tiles_hz: u32,
+    /// Number of tiles across the image vertically.
+    pub tiles_vt: u32,
+    /// Clip limit, expressed as a multiple of the mean tile occupancy.
+    pub clip_limit: f64,
+    /// Number of histogram bins (and lookup-table entries) per tile.
+    pub number_bins: u32,
+}
 
-    for (row_idx, row) in lookup_tables.iter_mut().enumerate() {
-        for (col_idx, table) in row.iter_mut().enumerate() {
-            let region_width = if col_idx == (tiles_hz - 1) as usize {
-                tile_width + input.dimensions().0 % tiles_hz
-            } else {
-                tile_width
-            };
-            let region_height = if row_idx == (tiles_vt - 1) as usize {
-                tile_height + input.dimensions().1 % tiles_vt
-            } else {
-                tile_height
-            };
-
-            let tile = SubImage::new(
-                &input,
-                tile_width * col_idx as u32,
-                tile_height * row_idx as u32,
-                region_width,
-                region_height,
-            );
-
-            let tile_hist = clip_histogram(histogram(&tile.to_image()), 40);
-            perform_gray_level_mapping(&tile_hist, table);
+impl Default for ClaheConfig {
+    fn default() -> Self {
+        ClaheConfig {
+            tiles_hz: 8,
+            tiles_vt: 8,
+            clip_limit: 3.0,
+            number_bins: 256,
         }
     }
+}
 
-    for (x, y, val) in input.enumerate_pixels() {
-        // use x and y to find four closest tile centers and their coordinates
-
-        if let Ok(tile) = is_corner_region(
-            x,
-            y,
-            tiles_hz,
-            tiles_vt,
-            input.dimensions().0,
-            input.dimensions().1,
-        ) {
-            let output_val = lookup_tables[tile.y as usize][tile.x as usize][val.0[0] as usize];
-            output.get_pixel_mut(x, y).0 = [output_val];
-        } else if let Ok(tiles) = is_border_region(
-            x,
-            y,
+impl ClaheConfig {
+    pub fn new(tiles_hz: u32, tiles_vt: u32, clip_limit: f64, number_bins: u32) -> Self {
+        ClaheConfig {
             tiles_hz,
             tiles_vt,
-            input.dimensions().0,
-            input.dimensions().1,
-        ) {
-            let tile_pixel0 =
-                lookup_tables[tiles.0.y as usize][tiles.0.x as usize][val.0[0] as usize];
-            let tile_pixel1 =
-                lookup_tables[tiles.1.y as usize][tiles.1.x as usize][val.0[0] as usize];
-            let weight = if tiles.0.x == tiles.1.x {
-                let tile_center0 = get_pixel_coordinate_from_tile_coordinate(tiles.0.x, tile_width);
-                (x as f32 - tile_center0 as f32) / tile_width as f32
-            } else if tiles.0.y == tiles.0.y {
-                let tile_center0 =
-                    get_pixel_coordinate_from_tile_coordinate(tiles.0.y, tile_height);
-                (y as f32 - tile_center0 as f32) / tile_height as f32
-            } else {
-                0.0
-            };
+            clip_limit,
+            number_bins,
+        }
+    }
 
-            output.get_pixel_mut(x, y).0 = if weight > 0.0 {
-                interpolate(Luma::from([tile_pixel0]), Luma::from([tile_pixel1]), 1. - weight).0
-            } else {
-                interpolate(
-                    Luma::from([tile_pixel1]),
-                    Luma::from([tile_pixel0]),
-                    -weight,
-                )
-                .0
-            };
-        } else {
-            let tiles = get_neighbor_tiles(
-                x,
-                y,
-                tiles_hz,
-                tiles_vt,
-                input.dimensions().0,
-                input.dimensions().1,
-            )
-            .unwrap();
-
-            let pixel_values = tiles.iter().map(|tile| lookup_tables[tile.y as usize][tile.x as usize][val.0[0] as usize]).collect::<Vec<u8>>();
-            let x_weight = (x - (tiles[0].x * tile_width + (tile_width / 2))) as f32 / tile_width as f32;
-            let y_weight = (y - (tiles[0].y * tile_height + (tile_height / 2))) as f32 / tile_height as f32;
-            let intermediate_1 = interpolate(Luma::from([pixel_values[0]]), Luma::from([pixel_values[1]]), 1.0 - x_weight);
-            let intermediate_2 = interpolate(Luma::from([pixel_values[3]]), Luma::from([pixel_values[2]]), 1.0 - x_weight);
-            output.get_pixel_mut(x, y).0 = interpolate::<Luma<u8>>(intermediate_1, intermediate_2, 1.0 - y_weight).0;
+    /// Checks that `tiles_hz`, `tiles_vt`, and `number_bins` are non-zero, since zero in
+    /// any of them leads to a divide-by-zero or empty-histogram panic downstream.
+    fn validate(&self) -> Result<(), ClaheConfigError> {
+        if self.tiles_hz == 0 || self.tiles_vt == 0 {
+            return Err(ClaheConfigError("tiles_hz and tiles_vt must be non-zero".to_string()));
         }
+        if self.number_bins == 0 {
+            return Err(ClaheConfigError("number_bins must be non-zero".to_string()));
+        }
+        Ok(())
     }
+}
 
-    Ok(output)
+#[derive(Debug)]
+struct ClaheConfigError(String);
+
+impl std::fmt::Display for ClaheConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "invalid ClaheConfig: {}", self.0)
+    }
 }
 
-fn clip_histogram(mut histogram: ChannelHistogram, limit: u32) -> ChannelHistogram {
-    let mut num_pixels_over_limit: u32 = 0;
+impl std::error::Error for ClaheConfigError {}
+
+/// Runs CLAHE on the HSV value channel only, to avoid the color shifts independent per-channel equalization causes.
+pub fn clahe_rgb(input: RgbImage, config: &ClaheConfig) -> Result<RgbImage, Box<dyn std::error::Error>> {
+    config.validate()?;
+
+    let (width, height) = input.dimensions();
+
+    let mut hue = vec![0f32; (width * height) as usize];
+    let mut saturation = vec![0f32; (width * height) as usize];
+    let mut value = GrayImage::new(width, height);
 
-    if histogram.channels.len() != 1 {
-        panic!("Too many channels!")
+    for (x, y, pixel) in input.enumerate_pixels() {
+        let (h, s, v) = rgb_to_hsv(pixel.0[0], pixel.0[1], pixel.0[2]);
+        let idx = (y * width + x) as usize;
+        hue[idx] = h;
+        saturation[idx] = s;
+        value.put_pixel(x, y, Luma([v]));
     }
 
-    for (_bin, count) in histogram.channels[0].iter_mut().enumerate() {
-        if *count > limit {
-            num_pixels_over_limit += *count - limit;
-            *count = limit;
-        }
+    let equalized_value = clahe(value, config)?;
+
+    let mut output = RgbImage::new(width, height);
+    for (x, y, pixel) in equalized_value.enumerate_pixels() {
+        let idx = (y * width + x) as usize;
+        let (r, g, b) = hsv_to_rgb(hue[idx], saturation[idx], pixel.0[0]);
+        output.put_pixel(x, y, Rgb([r, g, b]));
+    }
+
+    Ok(output)
+}
+
+fn rgb_to_hsv(r: u8, g: u8, b: u8) -> (f32, f32, u8) {
+    let rf = r as f32 / 255.0;
+    let gf = g as f32 / 255.0;
+    let bf = b as f32 / 255.0;
+
+    let max = rf.max(gf).max(bf);
+    let min = rf.min(gf).min(bf);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == rf {
+        60.0 * (((gf - bf) / delta).rem_euclid(6.0))
+    } else if max == gf {
+        60.0 * (((bf - rf) / delta) + 2.0)
+    } else {
+        60.0 * (((rf - gf) / delta) + 4.0)
+    };
+
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    let value = (max * 255.0).round() as u8;
+
+    (hue, saturation, value)
+}
+
+fn hsv_to_rgb(hue: f32, saturation: f32, value: u8) -> (u8, u8, u8) {
+    let v = value as f32 / 255.0;
+    let c = v * saturation;
+    let h_prime = hue / 60.0;
+    let x = c * (1.0 - (h_prime.rem_euclid(2.0) - 1.0).abs());
+    let m = v - c;
+
+    let (r1, g1, b1) = match h_prime as u32 {
+        0 => (c, x, 0.0),
+        1 => (x, c, 0.0),
+        2 => (0.0, c, x),
+        3 => (0.0, x, c),
+        4 => (x, 0.0, c),
+        _ => (c, 0.0, x),
+    };
+
+    (
+        ((r1 + m) * 255.0).round() as u8,
+        ((g1 + m) * 255.0).round() as u8,
+        ((b1 + m) * 255.0).round() as u8,
+    )
+}
+
+/// A pixel sample type CLAHE can operate on (currently `u8` and `u16`).
+///
+/// Captures the bits that differ by bit depth — histogram binning, the
+/// lookup-table fill value, and how a lookup-table entry is derived from
+/// a fraction of pixels seen — so [`clahe`] and [`clahe16`] can share one
+/// tile/lookup/remap pipeline instead of duplicating it per type.
+trait Sample: Primitive + Copy + Send + Sync + 'static {
+    /// The lookup-table fill value for an as-yet-unmapped bin.
+    fn empty() -> Self;
+    /// Maps a sample into one of `number_bins` histogram bins.
+    fn to_bin(self, number_bins: u32) -> usize;
+    /// Maps a `[0, 1]` fraction of pixels seen back onto this sample's range.
+    fn from_fraction(fraction: f64) -> Self;
+    /// Linearly blends two values, giving `left` the weight `left_weight`;
+    /// two horizontal blends and one vertical blend make the bilinear tile blend.
+    fn blend(left: Self, right: Self, left_weight: f32) -> Self;
+}
+
+impl Sample for u8 {
+    fn empty() -> Self {
+        0
     }
 
-    let excess_pixels_per_bin = num_pixels_over_limit / 256;
+    fn to_bin(self, number_bins: u32) -> usize {
+        ((self as u32 * number_bins) / 256) as usize
+    }
 
-    for count in histogram.channels[0].iter_mut() {
-        *count += excess_pixels_per_bin;
+    fn from_fraction(fraction: f64) -> Self {
+        (fraction * 255.0) as u8
     }
 
-    histogram
+    fn blend(left: Self, right: Self, left_weight: f32) -> Self {
+        interpolate(Luma([left]), Luma([right]), left_weight).0[0]
+    }
 }
 
-fn perform_gray_level_mapping(histogram: &ChannelHistogram, lookup_table: &mut Vec<u8>) {
-    let num_pixels: u32 = histogram.channels[0].iter().sum();
+impl Sample for u16 {
+    fn empty() -> Self {
+        0
+    }
 
-    let mut num_pixels_seen: u32 = 0;
-    for (index, entry) in lookup_table.iter_mut().enumerate() {
-        num_pixels_seen += histogram.channels[0][index];
+    fn to_bin(self, number_bins: u32) -> usize {
+        ((self as u64 * number_bins as u64) / 65536) as usize
+    }
 
-        let percent_pixels_seen = num_pixels_seen as f64 / num_pixels as f64;
-        *entry = (percent_pixels_seen * 255.0) as u8;
+    fn from_fraction(fraction: f64) -> Self {
+        (fraction * 65535.0) as u16
+    }
+
+    fn blend(left: Self, right: Self, left_weight: f32) -> Self {
+        interpolate(Luma([left]), Luma([right]), left_weight).0[0]
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-struct TileCoordinate {
-    pub x: u32,
-    pub y: u32,
+pub fn clahe(input: GrayImage, config: &ClaheConfig) -> Result<GrayImage, Box<dyn std::error::Error>> {
+    let (width, height) = input.dimensions();
+    let pixels = run_clahe(&input, config)?;
+    Ok(GrayImage::from_raw(width, height, pixels).unwrap())
 }
 
-fn is_corner_region(
-    x: u32,
-    y: u32,
-    tiles_hz: u32,
-    tiles_vt: u32,
-    dim_x: u32,
-    dim_y: u32,
-) -> Result<TileCoordinate, ()> {
-    let tile_width = dim_x / tiles_hz;
-    let tile_height = dim_y / tiles_vt;
-
-    if (x <= tile_width / 2) && (y <= tile_height / 2) {
-        // Top-left corner
-        Ok(TileCoordinate { x: 0, y: 0 })
-    } else if x > ((tile_width * tiles_hz) - tile_width / 2) && y <= tile_height / 2 {
-        // Top-right corner
-        Ok(TileCoordinate {
-            x: tiles_hz - 1,
-            y: 0,
-        })
-    } else if x > ((tile_width * tiles_hz) - tile_width / 2)
-        && y > ((tile_height * tiles_vt) - tile_height / 2)
-    {
-        // Bottom-right corner
-        Ok(TileCoordinate {
-            x: tiles_hz - 1,
-            y: tiles_vt - 1,
+/// Runs CLAHE on 16-bit grayscale input (common for medical, scientific
+/// and astronomy imagery), binning samples from the full `u16` range
+/// into `config.number_bins` bins and mapping back out to the full
+/// `u16` output range through a `u16` lookup table rather than a `u8`
+/// one. Samples that fall into the same bin read the same lookup-table
+/// entry, so input precision finer than one bin is not kept.
+pub fn clahe16(
+    input: ImageBuffer<Luma<u16>, Vec<u16>>,
+    config: &ClaheConfig,
+) -> Result<ImageBuffer<Luma<u16>, Vec<u16>>, Box<dyn std::error::Error>> {
+    let (width, height) = input.dimensions();
+    let pixels = run_clahe(&input, config)?;
+    Ok(ImageBuffer::from_raw(width, height, pixels).unwrap())
+}
+
+/// Shared tile/lookup/remap pipeline behind [`clahe`] and [`clahe16`].
+fn run_clahe<T: Sample>(
+    input: &ImageBuffer<Luma<T>, Vec<T>>,
+    config: &ClaheConfig,
+) -> Result<Vec<T>, Box<dyn std::error::Error>> {
+    config.validate()?;
+
+    let (width, height) = input.dimensions();
+    let tile_width = width / config.tiles_hz;
+    let tile_height = height / config.tiles_vt;
+
+    let lookup_tables = build_lookup_tables(input, config, tile_width, tile_height);
+    Ok(remap_pixels(input, &lookup_tables, config, tile_width, tile_height))
+}
+
+/// Computes one tile's clipped histogram and gray-level mapping.
+fn build_tile_lookup_table<T: Sample>(
+    input: &ImageBuffer<Luma<T>, Vec<T>>,
+    config: &ClaheConfig,
+    tile_width: u32,
+    tile_height: u32,
+    row_idx: u32,
+    col_idx: u32,
+) -> Vec<T> {
+    let number_bins = config.number_bins;
+
+    let region_width = if col_idx == config.tiles_hz - 1 {
+        tile_width + input.dimensions().0 % config.tiles_hz
+    } else {
+        tile_width
+    };
+    let region_height = if row_idx == config.tiles_vt - 1 {
+        tile_height + input.dimensions().1 % config.tiles_vt
+    } else {
+        tile_height
+    };
+
+    let tile = SubImage::new(
+        input,
+        tile_width * col_idx,
+        tile_height * row_idx,
+        region_width,
+        region_height,
+    );
+
+    let tile_image = tile.to_image();
+    let tile_hist = compute_histogram(&tile_image, number_bins);
+    let clip_limit = compute_clip_limit(tile_image.len() as u32, number_bins, config.clip_limit);
+    let clipped_hist = clip_histogram(tile_hist, clip_limit);
+
+    let mut table = vec![T::empty(); number_bins as usize];
+    perform_gray_level_mapping(&clipped_hist, &mut table);
+    table
+}
+
+#[cfg(not(feature = "rayon"))]
+fn build_lookup_tables<T: Sample>(
+    input: &ImageBuffer<Luma<T>, Vec<T>>,
+    config: &ClaheConfig,
+    tile_width: u32,
+    tile_height: u32,
+) -> Vec<Vec<Vec<T>>> {
+    (0..config.tiles_vt)
+        .map(|row_idx| {
+            (0..config.tiles_hz)
+                .map(|col_idx| build_tile_lookup_table(input, config, tile_width, tile_height, row_idx, col_idx))
+                .collect()
         })
-    } else if (x <= tile_width / 2) && y > ((tile_height * tiles_vt) - tile_height / 2) {
-        // Bottom-left corner
-        Ok(TileCoordinate {
-            x: 0,
-            y: tiles_vt - 1,
+        .collect()
+}
+
+#[cfg(feature = "rayon")]
+fn build_lookup_tables<T: Sample>(
+    input: &ImageBuffer<Luma<T>, Vec<T>>,
+    config: &ClaheConfig,
+    tile_width: u32,
+    tile_height: u32,
+) -> Vec<Vec<Vec<T>>> {
+    (0..config.tiles_vt)
+        .into_par_iter()
+        .map(|row_idx| {
+            (0..config.tiles_hz)
+                .into_par_iter()
+                .map(|col_idx| build_tile_lookup_table(input, config, tile_width, tile_height, row_idx, col_idx))
+                .collect()
         })
-    } else {
-        Err(())
-    }
+        .collect()
 }
 
-fn is_border_region(
+/// Finds the two tile indices straddling `coord` along one axis and how far `coord` sits between their centers, as a `[0, 1]` weight toward the higher-indexed tile.
+fn tile_interpolation_weights(coord: u32, tile_count: u32, tile_size: u32) -> (usize, usize, f32) {
+    let center_offset = coord as f32 - (tile_size as f32 / 2.0);
+    let float_index = center_offset / tile_size as f32;
+    let low = float_index.floor().max(0.0).min((tile_count - 1) as f32);
+    let high = (low + 1.0).min((tile_count - 1) as f32);
+    let weight = (float_index - low).clamp(0.0, 1.0);
+
+    (low as usize, high as usize, weight)
+}
+
+/// Looks up and bilinearly interpolates a single output pixel.
+fn compute_output_pixel<T: Sample>(
     x: u32,
     y: u32,
-    tiles_hz: u32,
-    tiles_vt: u32,
-    dim_x: u32,
-    dim_y: u32,
-) -> Result<(TileCoordinate, TileCoordinate), ()> {
-    let tile_width = dim_x / tiles_hz;
-    let tile_height = dim_y / tiles_vt;
-
-    if y <= (tile_height / 2) {
-        // Top border
-        let left_x = min((x - (tile_width / 2)) / tile_width, tiles_hz - 2);
-        let right_x = left_x + 1;
-        Ok((
-            TileCoordinate { x: left_x, y: 0 },
-            TileCoordinate { x: right_x, y: 0 },
-        ))
-    } else if y > ((tiles_vt * tile_height) - (tile_height / 2)) {
-        // Bottom border
-        let left_x = min((x - (tile_width / 2)) / tile_width, tiles_hz - 2);
-        let right_x = left_x + 1;
-        Ok((
-            TileCoordinate {
-                x: left_x,
-                y: tiles_vt - 1,
-            },
-            TileCoordinate {
-                x: right_x,
-                y: tiles_vt - 1,
-            },
-        ))
-    } else if x <= (tile_width / 2) {
-        // Left border
-        let top_y = min((y - (tile_height / 2)) / tile_height, tiles_vt - 2);
-        let bottom_y = top_y + 1;
-        Ok((
-            TileCoordinate { x: 0, y: top_y },
-            TileCoordinate { x: 0, y: bottom_y },
-        ))
-    } else if x > ((tiles_hz * tile_width) - (tile_width / 2)) {
-        // Right border
-        let top_y = min((y - (tile_height / 2)) / tile_height, tiles_vt - 2);
-        let bottom_y = top_y + 1;
-        Ok((
-            TileCoordinate {
-                x: tiles_hz - 1,
-                y: top_y,
-            },
-            TileCoordinate {
-                x: tiles_hz - 1,
-                y: bottom_y,
-            },
-        ))
-    } else {
-        Err(())
+    val: T,
+    lookup_tables: &[Vec<Vec<T>>],
+    config: &ClaheConfig,
+    tile_width: u32,
+    tile_height: u32,
+) -> T {
+    let bin = val.to_bin(config.number_bins);
+
+    let (col_low, col_high, x_weight) = tile_interpolation_weights(x, config.tiles_hz, tile_width);
+    let (row_low, row_high, y_weight) = tile_interpolation_weights(y, config.tiles_vt, tile_height);
+
+    let top_left = lookup_tables[row_low][col_low][bin];
+    let top_right = lookup_tables[row_low][col_high][bin];
+    let bottom_left = lookup_tables[row_high][col_low][bin];
+    let bottom_right = lookup_tables[row_high][col_high][bin];
+
+    let top = T::blend(top_left, top_right, 1.0 - x_weight);
+    let bottom = T::blend(bottom_left, bottom_right, 1.0 - x_weight);
+    T::blend(top, bottom, 1.0 - y_weight)
+}
+
+#[cfg(not(feature = "rayon"))]
+fn remap_pixels<T: Sample>(
+    input: &ImageBuffer<Luma<T>, Vec<T>>,
+    lookup_tables: &[Vec<Vec<T>>],
+    config: &ClaheConfig,
+    tile_width: u32,
+    tile_height: u32,
+) -> Vec<T> {
+    input
+        .enumerate_pixels()
+        .map(|(x, y, val)| compute_output_pixel(x, y, val.0[0], lookup_tables, config, tile_width, tile_height))
+        .collect()
+}
+
+#[cfg(feature = "rayon")]
+fn remap_pixels<T: Sample>(
+    input: &ImageBuffer<Luma<T>, Vec<T>>,
+    lookup_tables: &[Vec<Vec<T>>],
+    config: &ClaheConfig,
+    tile_width: u32,
+    tile_height: u32,
+) -> Vec<T> {
+    let (dim_x, dim_y) = input.dimensions();
+    (0..dim_y)
+        .into_par_iter()
+        .flat_map(|y| {
+            (0..dim_x)
+                .into_par_iter()
+                .map(|x| {
+                    let val = input.get_pixel(x, y).0[0];
+                    compute_output_pixel(x, y, val, lookup_tables, config, tile_width, tile_height)
+                })
+                .collect::<Vec<T>>()
+        })
+        .collect()
+}
+
+/// Builds a `number_bins`-wide histogram over a tile's pixels.
+fn compute_histogram<T: Sample>(tile: &ImageBuffer<Luma<T>, Vec<T>>, number_bins: u32) -> Vec<u32> {
+    let mut histogram = vec![0u32; number_bins as usize];
+
+    for pixel in tile.pixels() {
+        histogram[pixel.0[0].to_bin(number_bins)] += 1;
     }
+
+    histogram
 }
 
-fn get_neighbor_tiles(
-    x: u32,
-    y: u32,
-    tiles_hz: u32,
-    tiles_vt: u32,
-    dim_x: u32,
-    dim_y: u32,
-) -> Result<[TileCoordinate; 4], ()> {
-    let tile_width = dim_x / tiles_hz;
-    let tile_height = dim_y / tiles_vt;
-
-    let left_x = min((x - (tile_width / 2)) / tile_width, tiles_hz - 2);
-    let right_x = left_x + 1;
-    let top_y = min((y - (tile_height / 2)) / tile_height, tiles_vt - 2);
-    let bottom_y = top_y + 1;
-
-    Ok([
-        TileCoordinate {
-            x: left_x,
-            y: top_y,
-        },
-        TileCoordinate {
-            x: right_x,
-            y: top_y,
-        },
-        TileCoordinate {
-            x: right_x,
-            y: bottom_y,
-        },
-        TileCoordinate {
-            x: left_x,
-            y: bottom_y,
-        },
-    ])
+/// Converts a clip limit expressed as a multiple of the mean tile occupancy into a pixel count.
+fn compute_clip_limit(tile_pixel_count: u32, number_bins: u32, clip_limit: f64) -> u32 {
+    let mean_tile_occupancy = tile_pixel_count as f64 / number_bins as f64;
+    ((clip_limit * mean_tile_occupancy).round() as u32).max(1)
 }
 
-fn get_pixel_coordinate_from_tile_coordinate(tile_coord: u32, pixels_per_tile: u32) -> u32 {
-    (pixels_per_tile / 2) + (tile_coord * pixels_per_tile)
+/// Clips `histogram` to `limit` and redistributes the excess across the bins that remain below the limit, conserving the total pixel count.
+/// When `limit * histogram.len()` is below that total, every bin ends at `limit` and the pixels that do not fit are dropped.
+fn clip_histogram(mut histogram: Vec<u32>, limit: u32) -> Vec<u32> {
+    let number_bins = histogram.len() as u32;
+    let mut total_excess: u32 = histogram.iter().map(|&count| count.saturating_sub(limit)).sum();
+
+    while total_excess > 0 {
+        let redistribution = total_excess / number_bins;
+
+        if redistribution == 0 {
+            // Fewer pixels left than bins: clip any bin still over the
+            // limit (its excess is already folded into total_excess),
+            // then hand the rest out one at a time.
+            for count in histogram.iter_mut() {
+                if *count > limit {
+                    *count = limit;
+                }
+            }
+
+            let mut remaining = total_excess;
+            let mut placed = 0;
+            for count in histogram.iter_mut() {
+                if remaining == 0 {
+                    break;
+                }
+                if *count < limit {
+                    *count += 1;
+                    remaining -= 1;
+                    placed += 1;
+                }
+            }
+            if placed == 0 {
+                // Every bin is already at the limit; the remainder can't
+                // be placed without violating it, so stop here.
+                break;
+            }
+            total_excess = remaining;
+            continue;
+        }
+
+        let upper = limit.saturating_sub(redistribution);
+        let mut distributed = 0u32;
+        for count in histogram.iter_mut() {
+            if *count >= limit {
+                *count = limit;
+            } else if *count > upper {
+                distributed += limit - *count;
+                *count = limit;
+            } else {
+                *count += redistribution;
+                distributed += redistribution;
+            }
+        }
+
+        if distributed == 0 {
+            // Every bin is already at the limit; the remainder can't be
+            // placed without violating it, so stop here.
+            break;
+        }
+        total_excess -= distributed;
+    }
+
+    histogram
+}
+
+fn perform_gray_level_mapping<T: Sample>(histogram: &[u32], lookup_table: &mut [T]) {
+    let num_pixels: u32 = histogram.iter().sum();
+
+    let mut num_pixels_seen: u32 = 0;
+    for (index, entry) in lookup_table.iter_mut().enumerate() {
+        num_pixels_seen += histogram[index];
+
+        let percent_pixels_seen = num_pixels_seen as f64 / num_pixels as f64;
+        *entry = T::from_fraction(percent_pixels_seen);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram_with_one_bin_over_the_limit() -> Vec<u32> {
+        let mut histogram = vec![10u32; 256];
+        histogram[0] = 105;
+        histogram
+    }
+
+    #[test]
+    fn clip_histogram_conserves_total_pixel_count() {
+        let histogram = histogram_with_one_bin_over_the_limit();
+        let limit = 100;
+        let total: u32 = histogram.iter().sum();
+
+        let clipped = clip_histogram(histogram, limit);
+
+        assert_eq!(clipped.iter().sum::<u32>(), total);
+    }
+
+    #[test]
+    fn clip_histogram_caps_every_bin_at_the_limit() {
+        let histogram = histogram_with_one_bin_over_the_limit();
+        let limit = 100;
+
+        let clipped = clip_histogram(histogram, limit);
+
+        assert!(clipped.iter().all(|&count| count <= limit));
+    }
+
+    #[test]
+    fn tile_interpolation_weights_stays_in_bounds() {
+        let tile_count = 4;
+        let tile_size = 16;
+        for coord in 0..(tile_count * tile_size) {
+            let (low, high, weight) = tile_interpolation_weights(coord, tile_count, tile_size);
+            assert!(low < tile_count as usize);
+            assert!(high < tile_count as usize);
+            assert!((0.0..=1.0).contains(&weight));
+        }
+    }
+
+    #[test]
+    fn tile_interpolation_weights_is_continuous_across_a_tile_boundary() {
+        let tile_count = 4;
+        let tile_size = 16;
+
+        // Just below and just above a tile boundary should land on the same
+        // pair of tiles with nearly the same weight, not jump discontinuously.
+        let (low_before, high_before, weight_before) = tile_interpolation_weights(31, tile_count, tile_size);
+        let (low_after, high_after, weight_after) = tile_interpolation_weights(32, tile_count, tile_size);
+
+        assert_eq!((low_before, high_before), (low_after, high_after));
+        assert!((weight_after - weight_before).abs() < 0.1);
+    }
 }
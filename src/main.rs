@@ -7,10 +7,11 @@ fn main() {
         panic!("Please enter an image filename")
     };
 
-    let im = image::open(&Path::new(&file)).unwrap();
-    let output = clahe_rs::clahe(im.to_luma()).unwrap();
+    let im = image::open(Path::new(&file)).unwrap();
+    let config = clahe_rs::ClaheConfig::default();
+    let output = clahe_rs::clahe(im.to_luma8(), &config).unwrap();
 
     output
-        .save_with_format(&Path::new("output.png"), image::ImageFormat::Png)
+        .save_with_format(Path::new("output.png"), image::ImageFormat::Png)
         .unwrap();
 }